@@ -1,6 +1,4 @@
-use std::collections::HashMap;
-
-use banana::vm::{Op, Program, Value, Vm};
+use banana::vm::{Interner, Op, Program, Value, Vm};
 
 fn main() {
     let program = Program {
@@ -8,12 +6,7 @@ fn main() {
         ops: vec![Op::LoadConstant(0), Op::LoadConstant(1), Op::Add, Op::Print],
     };
 
-    let mut vm = Vm {
-        stack: vec![],
-        program,
-        globals: HashMap::new(),
-        ip: 0,
-    };
+    let mut vm = Vm::new(program, Interner::new());
 
-    vm.run_to_back();
+    vm.run_to_back().expect("uncaught exception");
 }
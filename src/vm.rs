@@ -1,4 +1,9 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub enum Value {
@@ -6,6 +11,13 @@ pub enum Value {
     Bool(bool),
     Number(f64),
     String(String),
+    Function(Rc<Function>),
+    /// A mutable, reference-counted list. Shared rather than copied on
+    /// assignment, so e.g. storing it in two globals aliases one buffer.
+    List(Rc<RefCell<Vec<Value>>>),
+    /// A caught exception, as pushed by `Vm::raise`. Keeps the `kind` around
+    /// so a catch block can branch on it instead of parsing `to_string()`.
+    Exception(Rc<Exception>),
 }
 
 impl Value {
@@ -15,6 +27,12 @@ impl Value {
             Value::Bool(b) => b.to_string(),
             Value::Number(n) => n.to_string(),
             Value::String(s) => s.clone(),
+            Value::Function(_) => "<function>".to_string(),
+            Value::List(items) => {
+                let rendered: Vec<String> = items.borrow().iter().map(Value::to_string).collect();
+                format!("[{}]", rendered.join(", "))
+            }
+            Value::Exception(exception) => exception.to_string(),
         }
     }
 
@@ -24,6 +42,9 @@ impl Value {
             Value::Bool(_) => "bool",
             Value::Number(_) => "number",
             Value::String(_) => "string",
+            Value::Function(_) => "function",
+            Value::List(_) => "list",
+            Value::Exception(_) => "exception",
         }
     }
 
@@ -33,23 +54,325 @@ impl Value {
             Value::Bool(b) => b,
             Value::Number(n) => n != 0.0,
             Value::String(s) => !s.is_empty(),
+            Value::Function(_) => true,
+            Value::List(items) => !items.borrow().is_empty(),
+            Value::Exception(_) => true,
+        }
+    }
+}
+
+/// A callable unit of bytecode: its own constant pool, its own ops, and the
+/// number of arguments it expects. Values of this type are what `Op::Call`
+/// invokes and what `Value::Function` wraps.
+#[derive(Debug)]
+pub struct Function {
+    pub ops: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub arity: usize,
+}
+
+/// One activation of a [`Function`] on the call stack: which function is
+/// running, its local variable slots, and where execution is up to within
+/// `func.ops`.
+#[derive(Debug)]
+pub struct CallFrame {
+    pub func: Rc<Function>,
+    pub locals: Vec<Value>,
+    pub ip: usize,
+}
+
+/// What kind of runtime error an [`Exception`] represents, so a `catch` block
+/// can branch on it without parsing the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionKind {
+    /// An operation was applied to a value of the wrong type (e.g. adding a
+    /// number to a list).
+    TypeError,
+    /// A global was read before it was ever set.
+    NameError,
+    /// A list or string was indexed out of bounds.
+    IndexError,
+    /// Execution was cancelled via `Vm::interrupt`.
+    Interrupted,
+    /// Any other fault in the VM itself (an out-of-bounds jump, a stack
+    /// overflow, ...).
+    RuntimeError,
+}
+
+impl fmt::Display for ExceptionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExceptionKind::TypeError => "TypeError",
+            ExceptionKind::NameError => "NameError",
+            ExceptionKind::IndexError => "IndexError",
+            ExceptionKind::Interrupted => "Interrupted",
+            ExceptionKind::RuntimeError => "RuntimeError",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A catchable runtime error. Produced wherever `run_next` used to `panic!`,
+/// and either caught by the nearest [`TryFrame`] or propagated out of
+/// `run_to_back` for the host to handle.
+#[derive(Clone, Debug)]
+pub struct Exception {
+    pub kind: ExceptionKind,
+    pub message: String,
+}
+
+impl Exception {
+    pub fn new(kind: ExceptionKind, message: impl Into<String>) -> Self {
+        Exception {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl From<&Exception> for Value {
+    fn from(exception: &Exception) -> Self {
+        Value::Exception(Rc::new(exception.clone()))
+    }
+}
+
+/// `Nil == Nil`, bools, numbers and strings compare by value, functions and
+/// lists by identity, and anything else (including mismatched types and
+/// exceptions) is unequal.
+fn values_equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+        (Value::Number(lhs), Value::Number(rhs)) => lhs == rhs,
+        (Value::String(lhs), Value::String(rhs)) => lhs == rhs,
+        (Value::Function(lhs), Value::Function(rhs)) => Rc::ptr_eq(lhs, rhs),
+        (Value::List(lhs), Value::List(rhs)) => Rc::ptr_eq(lhs, rhs),
+        _ => false,
+    }
+}
+
+/// Resolve a (possibly negative, Python-style) index against a collection of
+/// the given length, raising an `IndexError` if it's out of range.
+fn resolve_index(len: usize, index: f64) -> Result<usize, Exception> {
+    let index = index as i64;
+    let resolved = if index < 0 { index + len as i64 } else { index };
+    if resolved < 0 || resolved as usize >= len {
+        return Err(Exception::new(
+            ExceptionKind::IndexError,
+            format!("index {index} out of range for a collection of length {len}"),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+/// Ordering is only defined between two numbers (numerically) or two
+/// strings (lexicographically); anything else, including mixed types, is a
+/// type error.
+fn compare(lhs: &Value, rhs: &Value) -> Result<std::cmp::Ordering, Exception> {
+    match (lhs, rhs) {
+        (Value::Number(lhs), Value::Number(rhs)) => lhs
+            .partial_cmp(rhs)
+            .ok_or_else(|| Exception::new(ExceptionKind::TypeError, "cannot compare NaN")),
+        (Value::String(lhs), Value::String(rhs)) => Ok(lhs.cmp(rhs)),
+        (lhs, rhs) => Err(Exception::new(
+            ExceptionKind::TypeError,
+            format!("cannot compare '{}' with '{}'", lhs.get_type(), rhs.get_type()),
+        )),
+    }
+}
+
+/// Coerce both operands to integers and apply a bitwise/shift operator.
+fn int_op(lhs: Value, rhs: Value, f: impl Fn(i64, i64) -> i64) -> Result<Value, Exception> {
+    match (&lhs, &rhs) {
+        (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(f(*lhs as i64, *rhs as i64) as f64)),
+        _ => Err(Exception::new(
+            ExceptionKind::TypeError,
+            format!(
+                "cannot apply a bitwise operator to '{}' and '{}'",
+                lhs.get_type(),
+                rhs.get_type()
+            ),
+        )),
+    }
+}
+
+/// Evaluate a binary operator against its already-popped operands. Pulled
+/// out of `run_next`'s dispatch so adding an operator is one match arm here.
+fn binary_op(op: Op, lhs: Value, rhs: Value) -> Result<Value, Exception> {
+    match op {
+        Op::Add => match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs + rhs)),
+            (Value::String(lhs), rhs) => Ok(Value::String(lhs + &rhs.to_string())),
+            (lhs, Value::String(rhs)) => Ok(Value::String(lhs.to_string() + &rhs)),
+            (lhs, rhs) => Err(Exception::new(
+                ExceptionKind::TypeError,
+                format!("cannot add '{}' with '{}'", lhs.get_type(), rhs.get_type()),
+            )),
+        },
+        Op::Sub => match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs - rhs)),
+            (lhs, rhs) => Err(Exception::new(
+                ExceptionKind::TypeError,
+                format!("cannot subtract '{}' from '{}'", rhs.get_type(), lhs.get_type()),
+            )),
+        },
+        Op::Mul => match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs * rhs)),
+            (lhs, rhs) => Err(Exception::new(
+                ExceptionKind::TypeError,
+                format!("cannot multiply '{}' with '{}'", lhs.get_type(), rhs.get_type()),
+            )),
+        },
+        Op::Div => match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs / rhs)),
+            (lhs, rhs) => Err(Exception::new(
+                ExceptionKind::TypeError,
+                format!("cannot divide '{}' by '{}'", lhs.get_type(), rhs.get_type()),
+            )),
+        },
+        Op::Mod => match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs % rhs)),
+            (lhs, rhs) => Err(Exception::new(
+                ExceptionKind::TypeError,
+                format!("cannot modulo '{}' by '{}'", lhs.get_type(), rhs.get_type()),
+            )),
+        },
+        Op::Pow => match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs.powf(rhs))),
+            (lhs, rhs) => Err(Exception::new(
+                ExceptionKind::TypeError,
+                format!("cannot raise '{}' to the power of '{}'", lhs.get_type(), rhs.get_type()),
+            )),
+        },
+        Op::IntDiv => match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number((lhs / rhs).floor())),
+            (lhs, rhs) => Err(Exception::new(
+                ExceptionKind::TypeError,
+                format!("cannot divide '{}' by '{}'", lhs.get_type(), rhs.get_type()),
+            )),
+        },
+        Op::Eq => Ok(Value::Bool(values_equal(&lhs, &rhs))),
+        Op::Ne => Ok(Value::Bool(!values_equal(&lhs, &rhs))),
+        Op::Lt => Ok(Value::Bool(compare(&lhs, &rhs)?.is_lt())),
+        Op::Le => Ok(Value::Bool(compare(&lhs, &rhs)?.is_le())),
+        Op::Gt => Ok(Value::Bool(compare(&lhs, &rhs)?.is_gt())),
+        Op::Ge => Ok(Value::Bool(compare(&lhs, &rhs)?.is_ge())),
+        Op::BitAnd => int_op(lhs, rhs, |lhs, rhs| lhs & rhs),
+        Op::BitOr => int_op(lhs, rhs, |lhs, rhs| lhs | rhs),
+        Op::BitXor => int_op(lhs, rhs, |lhs, rhs| lhs ^ rhs),
+        // Mask the shift amount to 0..64 so an out-of-range or negative
+        // count can't make `<<`/`>>` panic.
+        Op::Shl => int_op(lhs, rhs, |lhs, rhs| lhs << (rhs as u32 & 63)),
+        Op::Shr => int_op(lhs, rhs, |lhs, rhs| lhs >> (rhs as u32 & 63)),
+        _ => unreachable!("binary_op called with a non-binary op"),
+    }
+}
+
+/// An interned name. Global lookups key on this instead of the name string
+/// itself, so repeated `SetGlobal`/`GetGlobal` don't re-hash and re-clone the
+/// same text on every access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// Maps distinct strings to small integer [`Symbol`]s and back. Names are
+/// interned once, up front (typically while a program is being built), so
+/// the ops they end up in can carry a pre-resolved `Symbol` rather than a
+/// constant index.
+#[derive(Default)]
+pub struct Interner {
+    names: Vec<String>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
         }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.symbols.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
     }
 }
 
+/// A single entry of the VM's try/catch stack, recording where to resume,
+/// how much of the operand stack to discard, and the call stack depth to
+/// unwind back to when unwinding into it.
+pub struct TryFrame {
+    pub catch_ip: usize,
+    pub stack_len: usize,
+    pub call_depth: usize,
+}
+
 #[repr(u8)]
+#[derive(Clone, Copy, Debug)]
 pub enum Op {
     LoadConstant(usize),
     TestNot,
     Jump(isize),
-    SetGlobal(usize),
-    GetGlobal(usize),
+    SetGlobal(Symbol),
+    GetGlobal(Symbol),
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    Pow,
+    IntDiv,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Neg,
+    Not,
     Print,
+    /// Push a try frame whose catch handler sits `catch_offset` ops ahead of
+    /// this one.
+    PushTry(usize),
+    /// Pop the most recently pushed try frame without unwinding into it.
+    PopTry,
+    /// Pop `argc` arguments and a callee off the operand stack and start
+    /// executing it in a new call frame.
+    Call(usize),
+    /// Pop the return value, discard the current call frame, and push the
+    /// return value onto the caller's stack.
+    Return,
+    GetLocal(usize),
+    SetLocal(usize),
+    /// Pop `n` values and build a list out of them, in the order they were
+    /// pushed.
+    NewList(usize),
+    /// Pop an index and a list/string, then push the element at that index.
+    Index,
+    /// Pop a value, an index, and a list, then mutate the list in place.
+    SetIndex,
+    /// Pop a list or string and push its length.
+    Len,
+    /// Pop a value-producing statement's result off the stack, recording it
+    /// as the VM's `last_value` before discarding it.
+    Pop,
 }
 
 pub struct Program {
@@ -57,114 +380,319 @@ pub struct Program {
     pub ops: Vec<Op>,
 }
 
+/// Default ceiling on call frame depth; see [`Vm::stack_max`].
+const DEFAULT_STACK_MAX: usize = 256;
+
 pub struct Vm {
     pub stack: Vec<Value>,
-    pub program: Program,
-    pub globals: HashMap<String, Value>,
-    pub ip: usize,
+    pub globals: HashMap<Symbol, Value>,
+    pub call_stack: Vec<CallFrame>,
+    pub try_frames: Vec<TryFrame>,
+    /// Maximum number of nested call frames before a call raises a "call
+    /// stack overflow" exception instead of growing further.
+    pub stack_max: usize,
+    /// The interner that resolved the `Symbol`s baked into this program's
+    /// ops, kept around so global names can be recovered for error messages.
+    pub interner: Interner,
+    /// The last value popped by `Op::Pop`, surfaced by `run_to_back` so a
+    /// REPL or embedder can observe an expression's result without an
+    /// explicit `Op::Print`.
+    pub last_value: Option<Value>,
+    /// Cooperative cancellation flag. A host can clone the handle returned
+    /// by `interrupt_handle` and set it from another thread (e.g. a Ctrl-C
+    /// handler) to stop a runaway program.
+    pub interrupt: Arc<AtomicBool>,
 }
 
+/// How many instructions to run between interrupt-flag checks, trading
+/// cancellation latency for the cost of an atomic load on every instruction.
+const INTERRUPT_CHECK_INTERVAL: usize = 256;
+
 impl Vm {
-    pub fn run_next(&mut self) {
-        match self.program.ops[self.ip] {
+    pub fn new(program: Program, interner: Interner) -> Self {
+        let entry = Rc::new(Function {
+            ops: program.ops,
+            constants: program.constants,
+            arity: 0,
+        });
+        Vm {
+            stack: vec![],
+            globals: HashMap::new(),
+            call_stack: vec![CallFrame {
+                func: entry,
+                locals: vec![],
+                ip: 0,
+            }],
+            try_frames: vec![],
+            stack_max: DEFAULT_STACK_MAX,
+            interner,
+            last_value: None,
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Clone a handle to this VM's interrupt flag so another thread can
+    /// request cancellation.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupt)
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.call_stack.last().expect("call stack is never empty while running")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.call_stack
+            .last_mut()
+            .expect("call stack is never empty while running")
+    }
+
+    /// Unwind to the nearest try frame, if any: drop call frames pushed
+    /// since it, truncate the operand stack back to where it was when the
+    /// frame was pushed, push the exception value, and resume at its catch
+    /// handler. With no try frame to catch it, the exception propagates to
+    /// the caller.
+    fn raise(&mut self, exception: Exception) -> Result<(), Exception> {
+        match self.try_frames.pop() {
+            Some(frame) => {
+                self.call_stack.truncate(frame.call_depth);
+                self.stack.truncate(frame.stack_len);
+                self.stack.push(Value::from(&exception));
+                self.frame_mut().ip = frame.catch_ip;
+                Ok(())
+            }
+            None => Err(exception),
+        }
+    }
+
+    pub fn run_next(&mut self) -> Result<(), Exception> {
+        let ip = self.frame().ip;
+        let op = self.frame().func.ops[ip];
+        match op {
             Op::LoadConstant(index) => {
-                self.stack.push(self.program.constants[index].clone());
+                self.stack.push(self.frame().func.constants[index].clone());
             }
             Op::TestNot => {
                 let value = self.stack.pop().unwrap();
                 if value.is_truthy() {
-                    self.ip += 1;
+                    self.frame_mut().ip += 1;
                 }
             }
-            Op::Jump(offset) => {
-                self.ip = self
-                    .ip
-                    .checked_add_signed(offset)
-                    .expect("Jump out of bounds");
-            }
-            Op::SetGlobal(index) => {
+            Op::Jump(offset) => match ip.checked_add_signed(offset) {
+                Some(target) => self.frame_mut().ip = target,
+                None => {
+                    return self.raise(Exception::new(ExceptionKind::RuntimeError, "jump out of bounds"));
+                }
+            },
+            Op::SetGlobal(symbol) => {
                 let value = self.stack.pop().unwrap();
-                let name = self.program.constants[index].to_string();
-                self.globals.insert(name, value);
+                self.globals.insert(symbol, value);
             }
-            Op::GetGlobal(index) => {
-                let name = self.program.constants[index].to_string();
-                let value = self.globals.get(&name).unwrap().clone();
-                self.stack.push(value);
+            Op::GetGlobal(symbol) => {
+                match self.globals.get(&symbol) {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => {
+                        let name = self.interner.resolve(symbol).to_string();
+                        return self.raise(Exception::new(
+                            ExceptionKind::NameError,
+                            format!("undefined global '{name}'"),
+                        ));
+                    }
+                }
             }
-            Op::Add => {
+            Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Mod
+            | Op::Pow
+            | Op::IntDiv
+            | Op::Eq
+            | Op::Ne
+            | Op::Lt
+            | Op::Le
+            | Op::Gt
+            | Op::Ge
+            | Op::BitAnd
+            | Op::BitOr
+            | Op::BitXor
+            | Op::Shl
+            | Op::Shr => {
                 let rhs = self.stack.pop().unwrap();
                 let lhs = self.stack.pop().unwrap();
-                self.stack.push(match (lhs, rhs) {
-                    (Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs + rhs),
-                    (Value::String(lhs), rhs) => Value::String(lhs + &rhs.to_string()),
-                    (lhs, Value::String(rhs)) => Value::String(lhs.to_string() + &rhs),
-                    (lhs, rhs) => panic!(
-                        "Cannot add '{:?}' with '{:?}'",
-                        lhs.get_type(),
-                        rhs.get_type()
-                    ),
-                });
+                match binary_op(op, lhs, rhs) {
+                    Ok(result) => self.stack.push(result),
+                    Err(exception) => return self.raise(exception),
+                }
             }
-            Op::Sub => {
-                let rhs = self.stack.pop().unwrap();
-                let lhs = self.stack.pop().unwrap();
-                self.stack.push(match (lhs, rhs) {
-                    (Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs - rhs),
-                    (lhs, rhs) => panic!(
-                        "Cannot subtract '{:?}' from '{:?}'",
-                        rhs.get_type(),
-                        lhs.get_type()
-                    ),
-                });
+            Op::Neg => {
+                let value = self.stack.pop().unwrap();
+                match value {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    other => {
+                        return self.raise(Exception::new(
+                            ExceptionKind::TypeError,
+                            format!("cannot negate '{}'", other.get_type()),
+                        ));
+                    }
+                }
             }
-            Op::Mul => {
-                let rhs = self.stack.pop().unwrap();
-                let lhs = self.stack.pop().unwrap();
-                self.stack.push(match (lhs, rhs) {
-                    (Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs * rhs),
-                    (lhs, rhs) => panic!(
-                        "Cannot multiply '{:?}' with '{:?}'",
-                        lhs.get_type(),
-                        rhs.get_type()
-                    ),
-                });
+            Op::Not => {
+                let value = self.stack.pop().unwrap();
+                self.stack.push(Value::Bool(!value.is_truthy()));
             }
-            Op::Div => {
-                let rhs = self.stack.pop().unwrap();
-                let lhs = self.stack.pop().unwrap();
-                self.stack.push(match (lhs, rhs) {
-                    (Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs / rhs),
-                    (lhs, rhs) => panic!(
-                        "Cannot divide '{:?}' by '{:?}'",
-                        lhs.get_type(),
-                        rhs.get_type()
-                    ),
-                });
+            Op::Print => {
+                let value = self.stack.pop().unwrap();
+                println!("{:?}", value);
             }
-            Op::Mod => {
-                let rhs = self.stack.pop().unwrap();
-                let lhs = self.stack.pop().unwrap();
-                self.stack.push(match (lhs, rhs) {
-                    (Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs % rhs),
-                    (lhs, rhs) => panic!(
-                        "Cannot modulo '{:?}' by '{:?}'",
-                        lhs.get_type(),
-                        rhs.get_type()
-                    ),
+            Op::PushTry(catch_offset) => {
+                self.try_frames.push(TryFrame {
+                    catch_ip: ip + catch_offset,
+                    stack_len: self.stack.len(),
+                    call_depth: self.call_stack.len(),
                 });
             }
-            Op::Print => {
+            Op::PopTry => {
+                self.try_frames.pop();
+            }
+            Op::Call(argc) => {
+                let callee = self.stack.pop().unwrap();
+                let func = match callee {
+                    Value::Function(func) => func,
+                    other => {
+                        return self.raise(Exception::new(
+                            ExceptionKind::TypeError,
+                            format!("'{}' is not callable", other.get_type()),
+                        ));
+                    }
+                };
+                if argc != func.arity {
+                    return self.raise(Exception::new(
+                        ExceptionKind::TypeError,
+                        format!("expected {} argument(s), got {argc}", func.arity),
+                    ));
+                }
+                if self.call_stack.len() >= self.stack_max {
+                    return self.raise(Exception::new(ExceptionKind::RuntimeError, "call stack overflow"));
+                }
+                let args_start = self.stack.len() - argc;
+                let locals = self.stack.split_off(args_start);
+                self.frame_mut().ip = ip + 1;
+                self.call_stack.push(CallFrame { func, locals, ip: 0 });
+                return Ok(());
+            }
+            Op::Return => {
+                let result = self.stack.pop().unwrap();
+                self.call_stack.pop();
+                self.stack.push(result);
+                return Ok(());
+            }
+            Op::GetLocal(index) => {
+                let value = self.frame().locals.get(index).cloned().unwrap_or(Value::Nil);
+                self.stack.push(value);
+            }
+            Op::SetLocal(index) => {
                 let value = self.stack.pop().unwrap();
-                println!("{:?}", value);
+                let locals = &mut self.frame_mut().locals;
+                if index >= locals.len() {
+                    locals.resize(index + 1, Value::Nil);
+                }
+                locals[index] = value;
+            }
+            Op::NewList(n) => {
+                let start = self.stack.len() - n;
+                let items = self.stack.split_off(start);
+                self.stack.push(Value::List(Rc::new(RefCell::new(items))));
+            }
+            Op::Index => {
+                let index = self.stack.pop().unwrap();
+                let collection = self.stack.pop().unwrap();
+                let result = match (&collection, &index) {
+                    (Value::List(items), Value::Number(index)) => {
+                        let items = items.borrow();
+                        resolve_index(items.len(), *index).map(|i| items[i].clone())
+                    }
+                    (Value::String(s), Value::Number(index)) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        resolve_index(chars.len(), *index).map(|i| Value::String(chars[i].to_string()))
+                    }
+                    (collection, index) => Err(Exception::new(
+                        ExceptionKind::TypeError,
+                        format!("cannot index '{}' with '{}'", collection.get_type(), index.get_type()),
+                    )),
+                };
+                match result {
+                    Ok(value) => self.stack.push(value),
+                    Err(exception) => return self.raise(exception),
+                }
+            }
+            Op::SetIndex => {
+                let value = self.stack.pop().unwrap();
+                let index = self.stack.pop().unwrap();
+                let collection = self.stack.pop().unwrap();
+                let result = match (&collection, &index) {
+                    (Value::List(items), Value::Number(index)) => {
+                        let mut items = items.borrow_mut();
+                        resolve_index(items.len(), *index).map(|i| items[i] = value.clone())
+                    }
+                    (collection, index) => Err(Exception::new(
+                        ExceptionKind::TypeError,
+                        format!("cannot index '{}' with '{}'", collection.get_type(), index.get_type()),
+                    )),
+                };
+                if let Err(exception) = result {
+                    return self.raise(exception);
+                }
+            }
+            Op::Len => {
+                let value = self.stack.pop().unwrap();
+                let len = match &value {
+                    Value::List(items) => items.borrow().len(),
+                    Value::String(s) => s.chars().count(),
+                    other => {
+                        return self.raise(Exception::new(
+                            ExceptionKind::TypeError,
+                            format!("'{}' has no length", other.get_type()),
+                        ));
+                    }
+                };
+                self.stack.push(Value::Number(len as f64));
+            }
+            Op::Pop => {
+                self.last_value = Some(self.stack.pop().unwrap());
             }
         }
-        self.ip += 1;
+        self.frame_mut().ip += 1;
+        Ok(())
     }
 
-    pub fn run_to_back(&mut self) {
-        while self.ip < self.program.ops.len() {
-            self.run_next();
+    pub fn run_to_back(&mut self) -> Result<Option<Value>, Exception> {
+        let mut since_interrupt_check = 0;
+        loop {
+            let Some(frame) = self.call_stack.last() else {
+                break;
+            };
+            if frame.ip >= frame.func.ops.len() {
+                // The entry frame completing ends the program; a callee
+                // that falls off the end of its ops without an explicit
+                // `Op::Return` implicitly returns `Nil` to its caller.
+                if self.call_stack.len() == 1 {
+                    break;
+                }
+                self.call_stack.pop();
+                self.stack.push(Value::Nil);
+                continue;
+            }
+            since_interrupt_check += 1;
+            if since_interrupt_check >= INTERRUPT_CHECK_INTERVAL {
+                since_interrupt_check = 0;
+                if self.interrupt.load(Ordering::Relaxed) {
+                    self.raise(Exception::new(ExceptionKind::Interrupted, "execution was interrupted"))?;
+                    continue;
+                }
+            }
+            self.run_next()?;
         }
+        Ok(self.last_value.clone())
     }
 }